@@ -0,0 +1,228 @@
+use crate::tile_matrix::reference_sequences;
+use crate::peak_matrix::create_feat_matrix;
+use crate::preprocessing::qc::{Contact, CellBarcode, HicQualityControl, ContactSummary};
+
+use std::collections::HashMap;
+
+use anndata_rs::base::AnnData;
+use anndata_rs::iterator::CsrIterator;
+use bed_utils::bed::{GenomicRange, BEDLike, Strand, tree::FeatureCounter};
+
+use hdf5::Result;
+
+/// Create a cell-by-(bin1, bin2) contact matrix from single-cell Hi-C contacts,
+/// analogous to [`crate::tile_matrix::create_tile_matrix`] but over pairs of genomic
+/// bins rather than single bins.
+///
+/// `contacts` must be grouped contiguously by barcode (e.g. sorted the way a `.pairs`
+/// file normally is), mirroring the per-cell ordering that [`crate::qc::read_insertions`]
+/// already provides for ATAC fragments; a barcode that reappears in a later, disjoint
+/// run has its [`HicQualityControl`] merged rather than overwritten, but still yields a
+/// second matrix row for that cell, so callers should pre-sort their input. Each
+/// contact's two anchors are mapped to their genome bins and the pair is linearized
+/// onto the upper triangle (`bin1 <= bin2`), so cis and trans contacts share a single
+/// feature axis.
+///
+/// Because the upper triangle is materialized densely (`O(num_bins^2)` features),
+/// `bin_size` should be chosen coarse enough (e.g. compartment- or arm-scale) that
+/// `num_bins^2` stays tractable; this panics rather than silently exhausting memory
+/// if it isn't. That cap forecloses fine-bin/whole-genome contact matrices, which
+/// would need a sparse (e.g. `HashMap`-keyed) per-cell accumulator instead of a dense
+/// `Vec` — worth revisiting before this is the recommended path for high-resolution
+/// scHi-C rather than compartment-scale analysis.
+///
+/// Returns the per-barcode [`HicQualityControl`], computed alongside the matrix, so
+/// low-quality cells can be filtered the same way `QualityControl` is used for ATAC.
+pub fn create_contact_matrix<I>(
+    anndata: &mut AnnData,
+    contacts: I,
+    bin_size: u64,
+    ) -> Result<HashMap<CellBarcode, HicQualityControl>>
+where
+    I: Iterator<Item = Contact>,
+{
+    let regions = reference_sequences(anndata);
+    let bins = BinPairIndex::new(&regions, bin_size);
+    assert!(
+        bins.num_pairs() <= MAX_CONTACT_FEATURES,
+        "bin_size {} yields {} bins ({} bin pairs), which exceeds the {} feature cap \
+         for a dense upper-triangle Hi-C matrix; pick a coarser bin_size",
+        bin_size, bins.num_bins(), bins.num_pairs(), MAX_CONTACT_FEATURES,
+    );
+    let feature_counter = BinPairCoverage::new(bins.clone());
+
+    let mut qc: HashMap<CellBarcode, ContactSummary> = HashMap::new();
+    let rows = group_contacts_by_barcode(contacts).map(|(barcode, group)| {
+        let mut summary = ContactSummary::new();
+        let tags: Vec<BinPairTag> = group.iter().filter_map(|contact| {
+            summary.update(contact);
+            let bin1 = bins.bin_of(&contact.chrom1, contact.start1)?;
+            let bin2 = bins.bin_of(&contact.chrom2, contact.start2)?;
+            Some(BinPairTag::new(bins.pair_index(bin1, bin2), contact.count))
+        }).collect();
+        qc.entry(barcode).or_insert_with(ContactSummary::new).merge(summary);
+        tags
+    });
+
+    create_feat_matrix(anndata, rows, feature_counter)?;
+    Ok(qc.into_iter().map(|(barcode, summary)| (barcode, summary.get_qc())).collect())
+}
+
+/// Upper bound on `num_bins^2` bin pairs a [`BinPairCoverage`] will materialize, to
+/// fail fast with a clear message instead of exhausting memory on a too-fine `bin_size`.
+const MAX_CONTACT_FEATURES: usize = 50_000_000;
+
+/// Group a stream of [`Contact`] records into consecutive per-barcode runs, the same
+/// shape `create_feat_matrix` expects for per-cell rows of tags.
+fn group_contacts_by_barcode<I>(contacts: I) -> impl Iterator<Item = (CellBarcode, Vec<Contact>)>
+where
+    I: Iterator<Item = Contact>,
+{
+    let mut contacts = contacts.peekable();
+    std::iter::from_fn(move || {
+        let first = contacts.next()?;
+        let barcode = first.barcode.clone();
+        let mut group = vec![first];
+        while contacts.peek().is_some_and(|next| next.barcode == barcode) {
+            group.push(contacts.next().unwrap());
+        }
+        Some((barcode, group))
+    })
+}
+
+/// Maps genomic coordinates to disjoint `bin_size` bins across the given reference
+/// sequences, and linearizes pairs of bins onto the upper triangle (including the
+/// diagonal), so a cell-by-(bin1, bin2) contact matrix can be expressed as a single
+/// feature axis.
+#[derive(Debug, Clone)]
+struct BinPairIndex {
+    bin_ids: Vec<String>,
+    offsets: HashMap<String, (usize, u64)>,
+    bin_size: u64,
+}
+
+impl BinPairIndex {
+    fn new(regions: &[GenomicRange], bin_size: u64) -> Self {
+        assert!(bin_size > 0, "bin_size must be positive");
+        let mut bin_ids = Vec::new();
+        let mut offsets = HashMap::new();
+        for region in regions {
+            let chrom_len = region.end();
+            offsets.insert(region.chrom().to_string(), (bin_ids.len(), chrom_len));
+            let mut pos = 0u64;
+            while pos < chrom_len {
+                let end = (pos + bin_size).min(chrom_len);
+                bin_ids.push(format!("{}:{}-{}", region.chrom(), pos, end));
+                pos += bin_size;
+            }
+        }
+        Self { bin_ids, offsets, bin_size }
+    }
+
+    fn num_bins(&self) -> usize {
+        self.bin_ids.len()
+    }
+
+    fn num_pairs(&self) -> usize {
+        let n = self.num_bins();
+        n * (n + 1) / 2
+    }
+
+    fn bin_of(&self, chrom: &str, pos: u64) -> Option<usize> {
+        let &(start_bin, chrom_len) = self.offsets.get(chrom)?;
+        if pos >= chrom_len {
+            return None;
+        }
+        Some(start_bin + (pos / self.bin_size) as usize)
+    }
+
+    /// Linearize an (unordered) pair of bins onto the upper triangle (`bin1 <= bin2`),
+    /// so cis and trans contacts share a single feature axis regardless of anchor order.
+    fn pair_index(&self, bin1: usize, bin2: usize) -> usize {
+        let (a, b) = if bin1 <= bin2 { (bin1, bin2) } else { (bin2, bin1) };
+        let n = self.num_bins();
+        a * (2 * n - a + 1) / 2 + (b - a)
+    }
+
+    fn pair_feature_ids(&self) -> Vec<String> {
+        let n = self.num_bins();
+        let mut ids = Vec::with_capacity(self.num_pairs());
+        for a in 0 .. n {
+            for b in a .. n {
+                ids.push(format!("{}|{}", self.bin_ids[a], self.bin_ids[b]));
+            }
+        }
+        ids
+    }
+}
+
+/// Carries a precomputed linearized (bin1, bin2) feature index through the same
+/// [`FeatureCounter`]/[`create_feat_matrix`] pipeline used for ATAC insertions, with
+/// `score` threading the contact's duplicate count the same way `Fragment::score`
+/// threads fragment counts.
+struct BinPairTag {
+    index: usize,
+    count: u32,
+}
+
+impl BinPairTag {
+    fn new(index: usize, count: u32) -> Self {
+        Self { index, count }
+    }
+}
+
+impl BEDLike for BinPairTag {
+    fn chrom(&self) -> &str { "" }
+    fn set_chrom(&mut self, _chrom: &str) -> &mut Self { self }
+    fn start(&self) -> u64 { self.index as u64 }
+    fn set_start(&mut self, start: u64) -> &mut Self {
+        self.index = start as usize;
+        self
+    }
+    fn end(&self) -> u64 { self.index as u64 + 1 }
+    fn set_end(&mut self, _end: u64) -> &mut Self { self }
+    fn name(&self) -> Option<&str> { None }
+    fn score(&self) -> Option<bed_utils::bed::Score> {
+        Some(self.count.try_into().unwrap())
+    }
+    fn strand(&self) -> Option<Strand> { None }
+}
+
+/// A [`FeatureCounter`] over linearized (bin1, bin2) pairs: since each [`BinPairTag`]
+/// already carries its resolved feature index (computed by [`BinPairIndex::pair_index`]),
+/// counting is a direct accumulation rather than an overlap query.
+struct BinPairCoverage {
+    bins: BinPairIndex,
+    counts: Vec<u32>,
+}
+
+impl BinPairCoverage {
+    fn new(bins: BinPairIndex) -> Self {
+        let n = bins.num_pairs();
+        Self { bins, counts: vec![0; n] }
+    }
+}
+
+impl FeatureCounter for BinPairCoverage {
+    type Value = u32;
+
+    fn reset(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+    }
+
+    fn num_features(&self) -> usize {
+        self.bins.num_pairs()
+    }
+
+    fn get_feature_ids(&self) -> Vec<String> {
+        self.bins.pair_feature_ids()
+    }
+
+    fn insert<B: BEDLike>(&mut self, tag: &B, count: u32) {
+        self.counts[tag.start() as usize] += count;
+    }
+
+    fn get_counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use bed_utils::bed::Strand;
+use rust_htslib::bam::{self, record::Aux, Read, Record};
+
+use super::qc::Fragment;
+
+/// Default BAM tag used to look up the cell barcode of a read.
+pub const DEFAULT_BARCODE_TAG: &[u8; 2] = b"CB";
+
+/// The Tn5 transposase inserts with a 9 bp duplication, which is conventionally
+/// corrected by shifting + strand reads 4 bp downstream and - strand reads 5 bp
+/// upstream so that the reported coordinates point at the center of the duplication.
+const TN5_SHIFT_FORWARD: i64 = 4;
+const TN5_SHIFT_REVERSE: i64 = -5;
+
+/// Builds [`Fragment`] records directly from a position- or name-sorted BAM file,
+/// without going through an intermediate fragment-file text representation.
+///
+/// Proper read pairs are mated by query name, Tn5-shifted, and reported as a single
+/// fragment spanning the leftmost 5' start to the rightmost 3' end. Unmated reads are
+/// dropped unless `keep_single_end` is enabled, in which case they are reported as
+/// stranded, single-ended fragments (see [`Fragment::to_insertions`]).
+#[derive(Debug, Clone)]
+pub struct BamFragmentGenerator {
+    barcode_tag: [u8; 2],
+    min_mapq: u8,
+    keep_single_end: bool,
+}
+
+impl Default for BamFragmentGenerator {
+    fn default() -> Self {
+        Self {
+            barcode_tag: *DEFAULT_BARCODE_TAG,
+            min_mapq: 30,
+            keep_single_end: false,
+        }
+    }
+}
+
+impl BamFragmentGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the BAM tag holding the cell barcode (default: `CB`).
+    pub fn barcode_tag(mut self, tag: [u8; 2]) -> Self {
+        self.barcode_tag = tag;
+        self
+    }
+
+    /// Set the minimum MAPQ a read (pair) must have to be retained.
+    pub fn min_mapq(mut self, min_mapq: u8) -> Self {
+        self.min_mapq = min_mapq;
+        self
+    }
+
+    /// If set, unmated single-end reads are kept as stranded fragments instead of
+    /// being dropped.
+    pub fn keep_single_end(mut self, keep: bool) -> Self {
+        self.keep_single_end = keep;
+        self
+    }
+
+    /// Consume a BAM reader and produce deduplicated [`Fragment`] records.
+    ///
+    /// Mates are paired by query name as records are streamed in, so the input may
+    /// be either position- or name-sorted. PCR duplicates are collapsed by an
+    /// identical (barcode, chrom, start, end) key, with collapsed copies folded into
+    /// `Fragment::count`. The BAM duplicate flag is not read: a flagged duplicate
+    /// whose key differs from every other fragment's is kept as its own fragment.
+    pub fn gen_fragments(&self, reader: &mut bam::Reader) -> Result<impl Iterator<Item = Fragment>> {
+        let header = reader.header().clone();
+        let mut mates: HashMap<Vec<u8>, Record> = HashMap::new();
+        let mut fragments: HashMap<(Option<String>, String, u64, u64), Fragment> = HashMap::new();
+
+        for result in reader.records() {
+            let record = result?;
+            if !self.is_usable(&record) {
+                continue;
+            }
+
+            if !record.is_paired() {
+                if self.keep_single_end {
+                    if let Some(frag) = self.single_end_fragment(&record, &header)? {
+                        self.insert(&mut fragments, frag);
+                    }
+                }
+                continue;
+            }
+
+            let qname = record.qname().to_vec();
+            match mates.remove(&qname) {
+                None => {
+                    mates.insert(qname, record);
+                }
+                Some(mate) => {
+                    let (r1, r2) = if record.is_first_in_template() {
+                        (record, mate)
+                    } else {
+                        (mate, record)
+                    };
+                    if let Some(frag) = self.pair_to_fragment(&r1, &r2, &header)? {
+                        self.insert(&mut fragments, frag);
+                    }
+                }
+            }
+        }
+
+        // Any reads left in `mates` lost their pair (e.g. the mate was filtered out
+        // or is genuinely missing). Optionally report them as single-end fragments.
+        if self.keep_single_end {
+            for (_, record) in mates {
+                if let Some(frag) = self.single_end_fragment(&record, &header)? {
+                    self.insert(&mut fragments, frag);
+                }
+            }
+        }
+
+        Ok(fragments.into_values())
+    }
+
+    fn is_usable(&self, record: &Record) -> bool {
+        !record.is_secondary()
+            && !record.is_supplementary()
+            && !record.is_unmapped()
+            && record.mapq() >= self.min_mapq
+    }
+
+    fn barcode(&self, record: &Record) -> Option<String> {
+        match record.aux(&self.barcode_tag) {
+            Ok(Aux::String(s)) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+
+    fn chrom(&self, record: &Record, header: &bam::HeaderView) -> Result<String> {
+        let tid = record.tid();
+        if tid < 0 {
+            bail!("read is unmapped");
+        }
+        let name = std::str::from_utf8(header.tid2name(tid as u32))?.to_string();
+        Ok(name)
+    }
+
+    fn single_end_fragment(&self, record: &Record, header: &bam::HeaderView) -> Result<Option<Fragment>> {
+        let chrom = self.chrom(record, header)?;
+        let is_fwd = !record.is_reverse();
+        let pos = record.pos();
+        let start = if is_fwd {
+            (pos + TN5_SHIFT_FORWARD).max(0) as u64
+        } else {
+            pos.max(0) as u64
+        };
+        let raw_end = record.reference_end();
+        let end = if is_fwd {
+            raw_end.max(0) as u64
+        } else {
+            // `raw_end` (from `reference_end()`) is exclusive, so the read's 5' base
+            // is `raw_end - 1`; shifted 5 bp upstream that's `raw_end - 6`, and since
+            // `to_insertions` reports the reverse insertion at `end - 1`, `end` must
+            // be `raw_end - 5`, i.e. `raw_end + TN5_SHIFT_REVERSE`.
+            (raw_end + TN5_SHIFT_REVERSE).max(0) as u64
+        };
+        if end <= start {
+            return Ok(None);
+        }
+        Ok(Some(Fragment {
+            chrom,
+            start,
+            end,
+            barcode: self.barcode(record),
+            count: 1,
+            strand: Some(if is_fwd { Strand::Forward } else { Strand::Reverse }),
+        }))
+    }
+
+    fn pair_to_fragment(&self, r1: &Record, r2: &Record, header: &bam::HeaderView) -> Result<Option<Fragment>> {
+        if !r1.is_proper_pair() || r1.tid() != r2.tid() {
+            return Ok(None);
+        }
+        let chrom = self.chrom(r1, header)?;
+
+        // The forward mate's 5' end defines the left (insertion) boundary and the
+        // reverse mate's 5' end defines the right boundary.
+        let (fwd, rev) = if r1.is_reverse() { (r2, r1) } else { (r1, r2) };
+        let start = (fwd.pos() + TN5_SHIFT_FORWARD).max(0) as u64;
+        // `reference_end()` is exclusive, so the reverse mate's 5' base is
+        // `reference_end() - 1`; shifted 5 bp upstream that's `reference_end() - 6`,
+        // and since `to_insertions` reports the reverse insertion at `end - 1`, `end`
+        // must be `reference_end() - 5`, i.e. `reference_end() + TN5_SHIFT_REVERSE`.
+        let end = (rev.reference_end() + TN5_SHIFT_REVERSE).max(0) as u64;
+        if end <= start {
+            return Ok(None);
+        }
+
+        Ok(Some(Fragment {
+            chrom,
+            start,
+            end,
+            barcode: self.barcode(r1).or_else(|| self.barcode(r2)),
+            count: 1,
+            strand: None,
+        }))
+    }
+
+    /// Fold a newly-built fragment into the accumulator, collapsing PCR duplicates.
+    ///
+    /// Fragments that agree on (barcode, chrom, start, end) are collapsed into one,
+    /// whether or not their reads carry the BAM duplicate flag; the flag itself is
+    /// not consulted.
+    fn insert(&self, fragments: &mut HashMap<(Option<String>, String, u64, u64), Fragment>, frag: Fragment) {
+        let key = (frag.barcode.clone(), frag.chrom.clone(), frag.start, frag.end);
+        fragments
+            .entry(key)
+            .and_modify(|existing| existing.count += frag.count)
+            .or_insert(frag);
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use bed_utils::bed::BEDLike;
+use coitrees::{COITree, IntervalNode, IntervalTree};
+
+/// A per-chromosome index over a static set of regions, built once from
+/// `(region, payload)` pairs and then queried repeatedly for overlaps.
+///
+/// Internally each chromosome's regions are stored in their own cache-oblivious
+/// interval tree ([`COITree`]), which turns the linear scan that
+/// `GIntervalMap::find`/`is_overlapped` perform into a stabbing query. This is the
+/// backend used by [`super::qc::TSSe::add`] and
+/// [`super::qc::fraction_of_reads_in_region`] so that overlap-heavy passes over
+/// hundreds of thousands of regions scale to millions of insertions.
+///
+/// `coitrees` stores coordinates as `i32`, so positions are narrowed from the `u64`
+/// domain `GIntervalMap` uses. Debug builds assert every coordinate fits; in release
+/// builds a position at or beyond `i32::MAX` (~2.1 Gbp into a contig) silently
+/// truncates/overflows and can produce wrong overlaps. All current reference genomes
+/// have contigs well under that, but this index should not be pointed at one that isn't.
+pub struct RegionIndex<T: Copy> {
+    trees: HashMap<String, COITree<T, u32>>,
+}
+
+impl<T: Copy> RegionIndex<T> {
+    /// Build an index from an iterator of `(region, payload)` pairs.
+    pub fn new<I, B>(regions: I) -> Self
+    where
+        I: IntoIterator<Item = (B, T)>,
+        B: BEDLike,
+    {
+        let mut by_chrom: HashMap<String, Vec<IntervalNode<T, u32>>> = HashMap::new();
+        for (region, payload) in regions {
+            debug_assert!(
+                region.end() <= i32::MAX as u64,
+                "region {}:{}-{} exceeds the i32 coordinate range coitrees supports",
+                region.chrom(), region.start(), region.end(),
+            );
+            // coitrees intervals are inclusive on both ends.
+            let first = region.start() as i32;
+            let last = region.end() as i32 - 1;
+            by_chrom.entry(region.chrom().to_string())
+                .or_default()
+                .push(IntervalNode::new(first, last, payload));
+        }
+        let trees = by_chrom.into_iter()
+            .map(|(chrom, nodes)| (chrom, COITree::new(&nodes)))
+            .collect();
+        Self { trees }
+    }
+
+    /// Invoke `callback` with the `(start, end_inclusive, payload)` of every indexed
+    /// region overlapping `query`.
+    pub fn query_overlaps<B: BEDLike>(&self, query: &B, mut callback: impl FnMut(i32, i32, &T)) {
+        if let Some(tree) = self.trees.get(query.chrom()) {
+            debug_assert!(
+                query.end() <= i32::MAX as u64,
+                "query {}:{}-{} exceeds the i32 coordinate range coitrees supports",
+                query.chrom(), query.start(), query.end(),
+            );
+            let first = query.start() as i32;
+            let last = query.end() as i32 - 1;
+            tree.query(first, last, |node| callback(node.first, node.last, &node.metadata));
+        }
+    }
+
+    /// Whether any indexed region overlaps `query`.
+    pub fn is_overlapped<B: BEDLike>(&self, query: &B) -> bool {
+        match self.trees.get(query.chrom()) {
+            Some(tree) => {
+                debug_assert!(
+                    query.end() <= i32::MAX as u64,
+                    "query {}:{}-{} exceeds the i32 coordinate range coitrees supports",
+                    query.chrom(), query.start(), query.end(),
+                );
+                let first = query.start() as i32;
+                let last = query.end() as i32 - 1;
+                tree.query_count(first, last) > 0
+            }
+            None => false,
+        }
+    }
+}
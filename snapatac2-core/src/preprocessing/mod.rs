@@ -0,0 +1,6 @@
+pub mod qc;
+pub mod bam;
+pub mod interval_index;
+
+pub use qc::{Fragment, Contact, CellBarcode, QualityControl, HicQualityControl};
+pub use interval_index::RegionIndex;
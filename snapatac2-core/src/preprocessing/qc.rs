@@ -5,6 +5,8 @@ use anyhow::Result;
 use serde::{Serialize, Deserialize};
 use smallvec::{SmallVec, smallvec};
 
+use super::interval_index::RegionIndex;
+
 pub type CellBarcode = String;
 
 /// Fragments from single-cell ATAC-seq experiment. Each fragment is represented
@@ -208,6 +210,79 @@ impl<'a> FragmentSummary<'a> {
     }
 }
 
+/// Per-barcode QC metrics for single-cell Hi-C contacts, analogous to [`QualityControl`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct HicQualityControl {
+    pub num_unique_contact: u64,
+    pub frac_cis: f64,
+    pub frac_trans: f64,
+    pub frac_duplicated: f64,
+    /// Log2-binned distribution of cis contact distances: bin `i` holds the number of
+    /// cis contacts whose anchor distance falls in `[2^i, 2^(i+1))` base pairs, except
+    /// that a distance of 0 is counted in bin 0 and the last bin (`i = 31`) also holds
+    /// every distance of `2^32` or more.
+    pub cis_distance_distribution: Vec<u64>,
+}
+
+const MAX_DISTANCE_BINS: usize = 32;
+
+pub(crate) struct ContactSummary {
+    num_unique_contact: u64,
+    num_total_contact: u64,
+    num_cis: u64,
+    num_trans: u64,
+    cis_distance_distribution: Vec<u64>,
+}
+
+impl ContactSummary {
+    pub(crate) fn new() -> Self {
+        ContactSummary {
+            num_unique_contact: 0,
+            num_total_contact: 0,
+            num_cis: 0,
+            num_trans: 0,
+            cis_distance_distribution: vec![0; MAX_DISTANCE_BINS],
+        }
+    }
+
+    pub(crate) fn update(&mut self, contact: &Contact) {
+        self.num_total_contact += contact.count as u64;
+        self.num_unique_contact += 1;
+        if contact.chrom1 == contact.chrom2 {
+            self.num_cis += 1;
+            let distance = contact.start1.abs_diff(contact.start2).max(1);
+            let bin = (63 - distance.leading_zeros() as usize).min(MAX_DISTANCE_BINS - 1);
+            self.cis_distance_distribution[bin] += 1;
+        } else {
+            self.num_trans += 1;
+        }
+    }
+
+    /// Fold another summary's counts into this one, e.g. when the same barcode's
+    /// contacts arrive as more than one contiguous run.
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.num_unique_contact += other.num_unique_contact;
+        self.num_total_contact += other.num_total_contact;
+        self.num_cis += other.num_cis;
+        self.num_trans += other.num_trans;
+        self.cis_distance_distribution.iter_mut()
+            .zip(other.cis_distance_distribution)
+            .for_each(|(a, b)| *a += b);
+    }
+
+    pub(crate) fn get_qc(self) -> HicQualityControl {
+        let frac_duplicated = 1.0 -
+            self.num_unique_contact as f64 / self.num_total_contact as f64;
+        HicQualityControl {
+            num_unique_contact: self.num_unique_contact,
+            frac_cis: self.num_cis as f64 / self.num_unique_contact as f64,
+            frac_trans: self.num_trans as f64 / self.num_unique_contact as f64,
+            frac_duplicated,
+            cis_distance_distribution: self.cis_distance_distribution,
+        }
+    }
+}
+
 fn moving_average(half_window: usize, arr: &[u64]) -> impl Iterator<Item = f64> + '_ {
     let n = arr.len();
     (0 .. n).map(move |i| {
@@ -249,9 +324,8 @@ pub fn read_tss<R: Read>(file: R) -> impl Iterator<Item = (String, u64, bool)> {
     })
 }
 
-#[derive(Debug, Clone)]
 pub struct TssRegions {
-    pub promoters: GIntervalMap<bool>,
+    pub promoters: RegionIndex<bool>,
     window_size: u64,
 }
 
@@ -260,10 +334,10 @@ impl TssRegions {
     /// The promoter region is defined as |--- window_size --- TSS --- window_size ---|,
     /// a total of 2 * window_size + 1 bp.
     pub fn new<I: IntoIterator<Item = (String, u64, bool)>>(iter: I, window_size: u64) -> Self {
-        let promoters = iter.into_iter().map( |(chr, tss, is_fwd)| {
+        let promoters = RegionIndex::new(iter.into_iter().map(|(chr, tss, is_fwd)| {
             let b = GenomicRange::new(chr, tss.saturating_sub(window_size), tss + window_size + 1);
             (b, is_fwd)
-        }).collect();
+        }));
         Self { promoters, window_size }
     }
 
@@ -280,19 +354,35 @@ pub fn make_promoter_map<I: Iterator<Item = (String, u64, bool)>>(iter: I, half_
         }).collect()
 }
 
-/// barcode counting.
-pub fn get_barcode_count<I>(fragments: I) -> HashMap<String, u64>
+/// Group an iterator of items by the barcode extracted from each one, counting how
+/// many items fall under each barcode.
+fn count_by_barcode<I, T>(items: I, barcode_of: impl Fn(T) -> CellBarcode) -> HashMap<String, u64>
 where
-    I: Iterator<Item = Fragment>,
+    I: Iterator<Item = T>,
 {
     let mut barcodes = HashMap::new();
-    fragments.for_each(|frag| {
-        let key = frag.barcode.unwrap().clone();
-        *barcodes.entry(key).or_insert(0) += 1;
+    items.for_each(|item| {
+        *barcodes.entry(barcode_of(item)).or_insert(0) += 1;
     });
     barcodes
 }
 
+/// barcode counting.
+pub fn get_barcode_count<I>(fragments: I) -> HashMap<String, u64>
+where
+    I: Iterator<Item = Fragment>,
+{
+    count_by_barcode(fragments, |frag| frag.barcode.unwrap())
+}
+
+/// barcode counting for scHi-C contacts, mirroring [`get_barcode_count`].
+pub fn get_contact_barcode_count<I>(contacts: I) -> HashMap<String, u64>
+where
+    I: Iterator<Item = Contact>,
+{
+    count_by_barcode(contacts, |contact| contact.barcode)
+}
+
 pub struct TSSe<'a> {
     promoters: &'a TssRegions,
     counts: Vec<u64>,
@@ -313,12 +403,12 @@ impl<'a> TSSe<'a> {
         frag.to_insertions().into_iter().for_each(|ins| {
             self.n_total += 1;
             let mut overlapped = false;
-            self.promoters.promoters.find(&ins).for_each(|(promoter, is_fwd)| {
+            self.promoters.promoters.query_overlaps(&ins, |start, end_inclusive, is_fwd| {
                 overlapped = true;
                 let pos = if *is_fwd {
-                        ins.start() - promoter.start()
+                        ins.start() - start as u64
                     } else {
-                        promoter.end() - 1 - ins.start()
+                        end_inclusive as u64 - ins.start()
                     };
                 self.counts[pos as usize] += 1;
             });
@@ -368,8 +458,8 @@ pub fn fragment_size_distribution<I>(data: I, max_size: usize) -> Vec<usize>
 }
 
 /// Count the fraction of the reads or read pairs in the given regions.
-pub fn fraction_of_reads_in_region<'a, I, D>(
-    iter: I, regions: &'a Vec<GIntervalMap<D>>, normalized: bool, count_as_insertion: bool,
+pub fn fraction_of_reads_in_region<'a, I, D: Copy>(
+    iter: I, regions: &'a Vec<RegionIndex<D>>, normalized: bool, count_as_insertion: bool,
 ) -> impl Iterator<Item = (Vec<Vec<f64>>, usize, usize)> + 'a
 where
     I: Iterator<Item = (Vec<Vec<Fragment>>, usize, usize)> + 'a,
@@ -407,4 +497,125 @@ where
         }).collect::<Vec<_>>();
         (frac, start, end)
     })
+}
+
+/// How to summarize the per-region values falling within a region when building a
+/// cell-by-region matrix, analogous to bedtools `map`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reducer {
+    Sum,
+    Mean,
+    Min,
+    Max,
+    Median,
+    Count,
+}
+
+/// Accumulates the per-region values for a single region under a given [`Reducer`].
+/// Sum/mean/min/max/count update in place as values arrive; median must buffer and
+/// sort its values once all have been seen.
+enum Accumulator {
+    Sum(f64),
+    Mean(f64, u64),
+    Min(Option<f64>),
+    Max(Option<f64>),
+    Count(u64),
+    Median(Vec<f64>),
+}
+
+impl Accumulator {
+    fn new(reducer: Reducer) -> Self {
+        match reducer {
+            Reducer::Sum => Accumulator::Sum(0.0),
+            Reducer::Mean => Accumulator::Mean(0.0, 0),
+            Reducer::Min => Accumulator::Min(None),
+            Reducer::Max => Accumulator::Max(None),
+            Reducer::Count => Accumulator::Count(0),
+            Reducer::Median => Accumulator::Median(Vec::new()),
+        }
+    }
+
+    fn update(&mut self, value: f64) {
+        match self {
+            Accumulator::Sum(s) => *s += value,
+            Accumulator::Mean(s, n) => { *s += value; *n += 1; }
+            Accumulator::Min(m) => *m = Some(m.map_or(value, |x| x.min(value))),
+            Accumulator::Max(m) => *m = Some(m.map_or(value, |x| x.max(value))),
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Median(values) => values.push(value),
+        }
+    }
+
+    fn finish(self) -> f64 {
+        match self {
+            Accumulator::Sum(s) => s,
+            Accumulator::Mean(s, n) => if n == 0 { 0.0 } else { s / n as f64 },
+            Accumulator::Min(m) => m.unwrap_or(0.0),
+            Accumulator::Max(m) => m.unwrap_or(0.0),
+            Accumulator::Count(n) => n as f64,
+            Accumulator::Median(mut values) => {
+                if values.is_empty() {
+                    0.0
+                } else {
+                    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    let mid = values.len() / 2;
+                    if values.len() % 2 == 0 {
+                        (values[mid - 1] + values[mid]) / 2.0
+                    } else {
+                        values[mid]
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Build a cell-by-region matrix by aggregating a per-fragment value (e.g. fragment
+/// count or fragment length, as produced by `value_fn`) over each region with a
+/// user-chosen [`Reducer`], analogous to bedtools `map`. This generalizes
+/// [`fraction_of_reads_in_region`], which is restricted to summing (and optionally
+/// normalizing) hit counts.
+pub fn aggregate_signal_over_regions<'a, I, D: Copy, F>(
+    iter: I,
+    regions: &'a Vec<RegionIndex<D>>,
+    reducer: Reducer,
+    count_as_insertion: bool,
+    value_fn: F,
+) -> impl Iterator<Item = (Vec<Vec<f64>>, usize, usize)> + 'a
+where
+    I: Iterator<Item = (Vec<Vec<Fragment>>, usize, usize)> + 'a,
+    F: Fn(&Fragment) -> f64 + 'a,
+{
+    let k = regions.len();
+    iter.map(move |(data, start, end)| {
+        let values = data.into_iter().map(|fragments| {
+            let mut accumulators: Vec<Accumulator> =
+                (0 .. k).map(|_| Accumulator::new(reducer)).collect();
+
+            if count_as_insertion {
+                fragments.iter().for_each(|frag| {
+                    let value = value_fn(frag);
+                    frag.to_insertions().into_iter().for_each(|ins| {
+                        regions.iter().enumerate().for_each(|(i, r)| {
+                            if r.is_overlapped(&ins) {
+                                accumulators[i].update(value);
+                            }
+                        });
+                    });
+                });
+            } else {
+                fragments.iter().for_each(|frag| {
+                    let value = value_fn(frag);
+                    regions.iter().enumerate().for_each(|(i, r)| {
+                        if r.is_overlapped(frag) {
+                            accumulators[i].update(value);
+                        }
+                    });
+                });
+            }
+
+            accumulators.into_iter().map(Accumulator::finish).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        (values, start, end)
+    })
 }
\ No newline at end of file
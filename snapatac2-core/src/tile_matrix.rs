@@ -9,15 +9,15 @@ use polars::prelude::{DataFrame, Series};
 
 use hdf5::{File, Result};
 use bed_utils::bed::{
-    GenomicRange,
-    tree::{SparseBinnedCoverage},
+    GenomicRange, BEDLike,
+    tree::{FeatureCounter, SparseBinnedCoverage},
 };
 
 /// Create cell by bin matrix, and compute qc matrix.
-/// 
+///
 /// # Arguments
-/// 
-/// * `file` - 
+///
+/// * `file` -
 /// * `fragments` -
 /// * `promoter` -
 /// * `region` -
@@ -30,14 +30,118 @@ pub fn create_tile_matrix(
     ) -> Result<()>
 where
 {
-    let df: DataFrame = anndata.uns.get("reference_sequences").unwrap().0
-        .as_ref().as_ref().read_elem();
-    let regions = df.column("reference_seq_length")
-        .unwrap().u64().unwrap().into_iter()
-        .zip(df.column("reference_seq_name").unwrap().utf8().unwrap())
-        .map(|(s, chr)| GenomicRange::new(chr.unwrap(), 0, s.unwrap())).collect();
+    let regions = reference_sequences(anndata);
     let feature_counter: SparseBinnedCoverage<'_, _, u32> =
         SparseBinnedCoverage::new(&regions, bin_size);
     let insertion = read_insertions(anndata)?;
     create_feat_matrix(anndata, insertion.iter(), feature_counter)
-}
\ No newline at end of file
+}
+
+/// Create a cell by (possibly overlapping) window matrix.
+///
+/// Unlike [`create_tile_matrix`], which bins each reference sequence into disjoint
+/// `bin_size` bins, this generates windows of size `window_size` at a fixed `step_size`
+/// apart: `[0, window_size)`, `[step_size, step_size + window_size)`, and so on, mirroring
+/// bedtools-style `windows` tiling. Passing `step_size == window_size` together with
+/// `clamp_trailing = true` reproduces the disjoint-bin behavior of
+/// [`create_tile_matrix`]. An insertion falling into several overlapping windows is
+/// counted into every one of them.
+///
+/// `clamp_trailing` controls the trailing windows of a reference sequence that would
+/// extend past its end (`start + window_size > length`; with `step_size < window_size`
+/// there can be several): when `true` they are truncated to the end of the reference
+/// sequence; when `false` they are dropped.
+pub fn create_tile_matrix_windowed(
+    anndata: &mut AnnData,
+    window_size: u64,
+    step_size: u64,
+    clamp_trailing: bool,
+    ) -> Result<()>
+{
+    let regions = reference_sequences(anndata);
+    let feature_counter = SlidingWindowCoverage::new(&regions, window_size, step_size, clamp_trailing);
+    let insertion = read_insertions(anndata)?;
+    create_feat_matrix(anndata, insertion.iter(), feature_counter)
+}
+
+pub(crate) fn reference_sequences(anndata: &AnnData) -> Vec<GenomicRange> {
+    let df: DataFrame = anndata.uns.get("reference_sequences").unwrap().0
+        .as_ref().as_ref().read_elem();
+    df.column("reference_seq_length")
+        .unwrap().u64().unwrap().into_iter()
+        .zip(df.column("reference_seq_name").unwrap().utf8().unwrap())
+        .map(|(s, chr)| GenomicRange::new(chr.unwrap(), 0, s.unwrap())).collect()
+}
+
+/// A [`FeatureCounter`] that tiles each reference sequence into windows of size
+/// `window_size`, spaced `step_size` apart, allowing adjacent windows to overlap
+/// when `step_size < window_size`.
+#[derive(Debug, Clone)]
+pub struct SlidingWindowCoverage {
+    windows: Vec<GenomicRange>,
+    counts: Vec<u32>,
+    index: std::collections::HashMap<String, Vec<usize>>,
+}
+
+impl SlidingWindowCoverage {
+    pub fn new(regions: &[GenomicRange], window_size: u64, step_size: u64, clamp_trailing: bool) -> Self {
+        assert!(step_size > 0, "step_size must be positive");
+        let mut windows = Vec::new();
+        let mut index: std::collections::HashMap<String, Vec<usize>> = std::collections::HashMap::new();
+        for region in regions {
+            let chrom_len = region.end();
+            let mut i = 0u64;
+            loop {
+                let start = i * step_size;
+                if start >= chrom_len {
+                    break;
+                }
+                let raw_end = start + window_size;
+                let (end, is_partial) = if raw_end > chrom_len {
+                    (chrom_len, true)
+                } else {
+                    (raw_end, false)
+                };
+                if is_partial && !clamp_trailing {
+                    break;
+                }
+                index.entry(region.chrom().to_string()).or_default().push(windows.len());
+                windows.push(GenomicRange::new(region.chrom(), start, end));
+                i += 1;
+            }
+        }
+        let n = windows.len();
+        Self { windows, counts: vec![0; n], index }
+    }
+}
+
+impl FeatureCounter for SlidingWindowCoverage {
+    type Value = u32;
+
+    fn reset(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+    }
+
+    fn num_features(&self) -> usize {
+        self.windows.len()
+    }
+
+    fn get_feature_ids(&self) -> Vec<String> {
+        self.windows.iter().map(|w| format!("{}:{}-{}", w.chrom(), w.start(), w.end())).collect()
+    }
+
+    fn insert<B: BEDLike>(&mut self, tag: &B, count: u32) {
+        if let Some(candidates) = self.index.get(tag.chrom()) {
+            for &i in candidates {
+                let window = &self.windows[i];
+                if window.start() < tag.end() && tag.start() < window.end() {
+                    self.counts[i] += count;
+                }
+            }
+        }
+    }
+
+    fn get_counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+}